@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use crate::memory::Memory;
+use crate::trap::Trap;
+use crate::vm::{IxType, IX_META_SIZE};
+
+// Source position of a token, used to point errors at the offending text.
+// Both fields are 1-based; a LoadFault error, which has no source token,
+// carries line 0, col 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    UnknownRegister(String),
+    BadOperand(String),
+    WrongOperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    NumberOutOfRange(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    // The assembled bytes could not be placed in the code segment.
+    LoadFault(Trap),
+}
+
+// An assembler error carrying the span of the token it refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    pub span: Span,
+}
+
+impl AsmError {
+    fn new(kind: AsmErrorKind, span: Span) -> AsmError {
+        AsmError { kind, span }
+    }
+}
+
+// A single parsed operand. Label references are resolved to a two-byte
+// address in the second pass once every label's address is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Reg(u8),
+    Num(u16),
+    Label(String),
+}
+
+struct Token {
+    text: String,
+    span: Span,
+}
+
+// A decoded source line: an optional label definition plus, if present, a
+// mnemonic and its operands.
+struct Line {
+    mnemonic: Option<Token>,
+    operands: Vec<(Operand, Span)>,
+}
+
+fn reg_code(name: &str) -> Option<u8> {
+    match name {
+        "a" => Some(0),
+        "b" => Some(1),
+        "c" => Some(2),
+        "d" => Some(3),
+        "e" => Some(4),
+        "f" => Some(5),
+        "h" => Some(6),
+        "l" => Some(7),
+        "sp" => Some(8),
+        "pc" => Some(9),
+        "dp" => Some(10),
+        _ => None,
+    }
+}
+
+// Encoded byte length of an instruction, used in the first pass to assign
+// addresses to labels before any operand is resolved.
+fn ix_len(mnemonic: &str) -> Option<u16> {
+    let operand_bytes = match mnemonic {
+        "nop" => return Some(1),
+        "ret" | "reti" => 0,
+        "push" | "pop" => 1,
+        "mov" | "jmp" | "jnz" | "jz" | "call" => 2,
+        "ldm" | "stm" | "add" | "sub" | "mul" => 3,
+        "div" => 4,
+        _ => return None,
+    };
+    Some(IX_META_SIZE + operand_bytes)
+}
+
+// Splits a line into tokens, stripping `;` comments and treating commas as
+// whitespace, while remembering each token's 1-based column.
+fn tokenize_line(line: &str, line_no: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut start_col = 0;
+
+    let flush = |current: &mut String, start_col: usize, tokens: &mut Vec<Token>| {
+        if !current.is_empty() {
+            tokens.push(Token {
+                text: std::mem::take(current),
+                span: Span {
+                    line: line_no,
+                    col: start_col,
+                },
+            });
+        }
+    };
+
+    for (idx, ch) in line.char_indices() {
+        if ch == ';' {
+            break;
+        }
+        if ch.is_whitespace() || ch == ',' {
+            flush(&mut current, start_col, &mut tokens);
+        } else {
+            if current.is_empty() {
+                start_col = idx + 1;
+            }
+            current.push(ch);
+        }
+    }
+    flush(&mut current, start_col, &mut tokens);
+    tokens
+}
+
+fn parse_number(text: &str, span: Span) -> Result<u16, AsmError> {
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse::<u16>()
+    };
+    parsed.map_err(|_| AsmError::new(AsmErrorKind::BadOperand(text.to_string()), span))
+}
+
+fn parse_operand(token: &Token) -> Result<Operand, AsmError> {
+    if let Some(code) = reg_code(&token.text) {
+        Ok(Operand::Reg(code))
+    } else if token.text.starts_with(|c: char| c.is_ascii_digit()) {
+        Ok(Operand::Num(parse_number(&token.text, token.span)?))
+    } else {
+        Ok(Operand::Label(token.text.clone()))
+    }
+}
+
+// Assembles src into the byte layout exec_ix expects (opcode, data-size,
+// operands). Labels (name:) may be referenced before they are defined;
+// references are resolved in a second pass.
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    // First pass: parse every line and record each label's byte address.
+    let mut lines: Vec<Line> = Vec::new();
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut addr: u16 = 0;
+
+    for (idx, raw) in src.lines().enumerate() {
+        let mut tokens = tokenize_line(raw, idx + 1).into_iter();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+
+        let mnemonic = if let Some(label) = first.text.strip_suffix(':') {
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(AsmError::new(
+                    AsmErrorKind::DuplicateLabel(label.to_string()),
+                    first.span,
+                ));
+            }
+            match tokens.next() {
+                Some(tok) => tok,
+                None => {
+                    lines.push(Line {
+                        mnemonic: None,
+                        operands: Vec::new(),
+                    });
+                    continue;
+                }
+            }
+        } else {
+            first
+        };
+
+        let len = ix_len(&mnemonic.text).ok_or_else(|| {
+            AsmError::new(
+                AsmErrorKind::UnknownMnemonic(mnemonic.text.clone()),
+                mnemonic.span,
+            )
+        })?;
+        addr = addr
+            .checked_add(len)
+            .ok_or_else(|| AsmError::new(AsmErrorKind::NumberOutOfRange(mnemonic.text.clone()), mnemonic.span))?;
+
+        let mut operands = Vec::new();
+        for tok in tokens {
+            let span = tok.span;
+            operands.push((parse_operand(&tok)?, span));
+        }
+
+        lines.push(Line {
+            mnemonic: Some(mnemonic),
+            operands,
+        });
+    }
+
+    // Second pass: encode, resolving label references now that all addresses
+    // are known.
+    let mut out = Vec::with_capacity(addr as usize);
+    for line in &lines {
+        let Some(mnemonic) = &line.mnemonic else {
+            continue;
+        };
+        encode_line(mnemonic, &line.operands, &labels, &mut out)?;
+    }
+
+    Ok(out)
+}
+
+fn resolve_imm(op: &Operand, span: Span) -> Result<u8, AsmError> {
+    match op {
+        Operand::Num(v) if *v <= u8::MAX as u16 => Ok(*v as u8),
+        Operand::Num(_) => Err(AsmError::new(
+            AsmErrorKind::NumberOutOfRange(format!("{op:?}")),
+            span,
+        )),
+        Operand::Label(text) => Err(AsmError::new(
+            AsmErrorKind::BadOperand(text.clone()),
+            span,
+        )),
+        Operand::Reg(_) => Err(AsmError::new(
+            AsmErrorKind::BadOperand("expected immediate".to_string()),
+            span,
+        )),
+    }
+}
+
+// Resolves a branch target, which may be either a literal 16-bit address or a
+// label whose address was recorded in the first pass.
+fn resolve_addr(
+    op: &Operand,
+    span: Span,
+    labels: &HashMap<String, u16>,
+) -> Result<u16, AsmError> {
+    match op {
+        Operand::Num(v) => Ok(*v),
+        Operand::Label(name) => labels.get(name).copied().ok_or_else(|| {
+            AsmError::new(AsmErrorKind::UndefinedLabel(name.clone()), span)
+        }),
+        Operand::Reg(_) => Err(AsmError::new(
+            AsmErrorKind::BadOperand("expected address or label".to_string()),
+            span,
+        )),
+    }
+}
+
+fn resolve_reg(op: &Operand, span: Span) -> Result<u8, AsmError> {
+    match op {
+        Operand::Reg(code) => Ok(*code),
+        Operand::Label(text) => Err(AsmError::new(
+            AsmErrorKind::UnknownRegister(text.clone()),
+            span,
+        )),
+        Operand::Num(_) => Err(AsmError::new(
+            AsmErrorKind::BadOperand("expected register".to_string()),
+            span,
+        )),
+    }
+}
+
+fn expect_arity(
+    mnemonic: &Token,
+    operands: &[(Operand, Span)],
+    expected: usize,
+) -> Result<(), AsmError> {
+    if operands.len() != expected {
+        return Err(AsmError::new(
+            AsmErrorKind::WrongOperandCount {
+                mnemonic: mnemonic.text.clone(),
+                expected,
+                found: operands.len(),
+            },
+            mnemonic.span,
+        ));
+    }
+    Ok(())
+}
+
+fn encode_line(
+    mnemonic: &Token,
+    operands: &[(Operand, Span)],
+    labels: &HashMap<String, u16>,
+    out: &mut Vec<u8>,
+) -> Result<(), AsmError> {
+    match mnemonic.text.as_str() {
+        "nop" => {
+            expect_arity(mnemonic, operands, 0)?;
+            out.push(IxType::NOP as u8);
+        }
+        "mov" => {
+            expect_arity(mnemonic, operands, 2)?;
+            let reg = resolve_reg(&operands[0].0, operands[0].1)?;
+            let imm = resolve_imm(&operands[1].0, operands[1].1)?;
+            out.extend_from_slice(&[IxType::MOV as u8, 2, reg, imm]);
+        }
+        "ldm" | "stm" | "add" | "sub" | "mul" => {
+            expect_arity(mnemonic, operands, 3)?;
+            let high = resolve_imm(&operands[0].0, operands[0].1)?;
+            let low = resolve_imm(&operands[1].0, operands[1].1)?;
+            let reg = resolve_reg(&operands[2].0, operands[2].1)?;
+            let opcode = match mnemonic.text.as_str() {
+                "ldm" => IxType::LDM,
+                "stm" => IxType::STM,
+                "add" => IxType::ADD,
+                "sub" => IxType::SUB,
+                _ => IxType::MUL,
+            };
+            out.extend_from_slice(&[opcode as u8, 3, high, low, reg]);
+        }
+        "div" => {
+            expect_arity(mnemonic, operands, 4)?;
+            let high = resolve_imm(&operands[0].0, operands[0].1)?;
+            let low = resolve_imm(&operands[1].0, operands[1].1)?;
+            let reg_q = resolve_reg(&operands[2].0, operands[2].1)?;
+            let reg_r = resolve_reg(&operands[3].0, operands[3].1)?;
+            out.extend_from_slice(&[IxType::DIV as u8, 4, high, low, reg_q, reg_r]);
+        }
+        "jmp" | "jnz" | "jz" | "call" => {
+            expect_arity(mnemonic, operands, 1)?;
+            let addr = resolve_addr(&operands[0].0, operands[0].1, labels)?;
+            let opcode = match mnemonic.text.as_str() {
+                "jmp" => IxType::JMP,
+                "jnz" => IxType::JNZ,
+                "jz" => IxType::JZ,
+                _ => IxType::CALL,
+            };
+            out.extend_from_slice(&[opcode as u8, 2, (addr >> 8) as u8, (addr & 0xff) as u8]);
+        }
+        "push" | "pop" => {
+            expect_arity(mnemonic, operands, 1)?;
+            let reg = resolve_reg(&operands[0].0, operands[0].1)?;
+            let opcode = if mnemonic.text == "push" {
+                IxType::PUSH
+            } else {
+                IxType::POP
+            };
+            out.extend_from_slice(&[opcode as u8, 1, reg]);
+        }
+        "ret" | "reti" => {
+            expect_arity(mnemonic, operands, 0)?;
+            let opcode = if mnemonic.text == "ret" {
+                IxType::RET
+            } else {
+                IxType::RETI
+            };
+            out.extend_from_slice(&[opcode as u8, 0]);
+        }
+        other => {
+            return Err(AsmError::new(
+                AsmErrorKind::UnknownMnemonic(other.to_string()),
+                mnemonic.span,
+            ))
+        }
+    }
+    Ok(())
+}
+
+// Assembles src and loads the resulting bytes into the code segment at
+// from_addr via Memory::load_ix, returning the number of bytes written.
+pub fn assemble_and_load(mem: &mut Memory, from_addr: u16, src: &str) -> Result<usize, AsmError> {
+    let bytes = assemble(src)?;
+    mem.load_ix(from_addr, &bytes).map_err(|trap| {
+        AsmError::new(
+            AsmErrorKind::LoadFault(trap),
+            Span { line: 0, col: 0 },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::IxType;
+
+    #[test]
+    fn forward_label_reference_resolves() {
+        let bytes = assemble("jmp skip\nmov a, 1\nskip:\nnop\n").unwrap();
+        assert_eq!(
+            bytes,
+            vec![IxType::JMP as u8, 2, 0, 8, IxType::MOV as u8, 2, 0, 1, IxType::NOP as u8]
+        );
+    }
+
+    #[test]
+    fn backward_label_reference_resolves() {
+        let bytes = assemble("start:\nmov a, 1\njmp start\n").unwrap();
+        assert_eq!(
+            bytes,
+            vec![IxType::MOV as u8, 2, 0, 1, IxType::JMP as u8, 2, 0, 0]
+        );
+    }
+
+    #[test]
+    fn undefined_label_error_carries_the_reference_span() {
+        let err = assemble("jmp missing\n").unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::UndefinedLabel("missing".to_string()));
+        assert_eq!(err.span, Span { line: 1, col: 5 });
+    }
+
+    #[test]
+    fn duplicate_label_error_carries_the_redefinition_span() {
+        let err = assemble("a:\na:\nnop\n").unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::DuplicateLabel("a".to_string()));
+        assert_eq!(err.span, Span { line: 2, col: 1 });
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+
+use crate::memory::Memory;
+use crate::vm::{IxType, Register, IX_DATA_OFFSET, IX_SIZE_OFFSET, IX_META_SIZE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisasmError {
+    InvalidInstruction(u8),
+    InvalidRegister(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Register),
+    Imm(u8),
+    Addr(u16),
+}
+
+// One decoded instruction: its address in the code segment, its type, and its
+// decoded operands.
+#[derive(Debug, Clone)]
+pub struct DisasmItem {
+    pub addr: u16,
+    pub ix_type: IxType,
+    pub operands: Vec<Operand>,
+}
+
+impl DisasmItem {
+    pub fn mnemonic(&self) -> &'static str {
+        match self.ix_type {
+            IxType::NOP => "nop",
+            IxType::MOV => "mov",
+            IxType::LDM => "ldm",
+            IxType::STM => "stm",
+            IxType::ADD => "add",
+            IxType::SUB => "sub",
+            IxType::MUL => "mul",
+            IxType::DIV => "div",
+            IxType::JMP => "jmp",
+            IxType::JNZ => "jnz",
+            IxType::JZ => "jz",
+            IxType::PUSH => "push",
+            IxType::POP => "pop",
+            IxType::CALL => "call",
+            IxType::RET => "ret",
+            IxType::RETI => "reti",
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{reg:?}"),
+            Operand::Imm(v) => write!(f, "{v:#04x}"),
+            Operand::Addr(a) => write!(f, "{a:#06x}"),
+        }
+    }
+}
+
+impl fmt::Display for DisasmItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06x}: {}", self.addr, self.mnemonic())?;
+        for (idx, op) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if idx == 0 { " " } else { ", " }, op)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_reg(byte: u8) -> Result<Register, DisasmError> {
+    Register::try_from(byte).map_err(|_| DisasmError::InvalidRegister(byte))
+}
+
+// Number of operand bytes `ix_type` reads, mirroring the layout asm.rs
+// encodes. Used to catch a declared ix_data_size that is too short for the
+// opcode before indexing into it.
+fn expected_data_len(ix_type: IxType) -> usize {
+    match ix_type {
+        IxType::NOP | IxType::RET | IxType::RETI => 0,
+        IxType::PUSH | IxType::POP => 1,
+        IxType::MOV | IxType::JMP | IxType::JNZ | IxType::JZ | IxType::CALL => 2,
+        IxType::LDM | IxType::STM | IxType::ADD | IxType::SUB | IxType::MUL => 3,
+        IxType::DIV => 4,
+    }
+}
+
+// Walks the code segment from start, decoding instructions with the same
+// opcode/data-size/operand layout that parseand_exec_ixs_seq reads, and
+// returns one DisasmItem per instruction. Decoding stops cleanly at a NOP
+// (included as the final item) or at the end of the segment; an unknown
+// opcode is reported as DisasmError::InvalidInstruction rather than panicking.
+pub fn disasm(mem: &Memory, start: u16) -> Result<Vec<DisasmItem>, DisasmError> {
+    let mut items = Vec::new();
+    let mut pc = start;
+
+    // A failed read means we have walked off the end of the segment, which is
+    // a clean stop rather than an error.
+    while let Ok(opcode) = mem.read_code_seg(pc) {
+        let ix_type =
+            IxType::try_from(opcode).map_err(|_| DisasmError::InvalidInstruction(opcode))?;
+
+        if let IxType::NOP = ix_type {
+            items.push(DisasmItem {
+                addr: pc,
+                ix_type,
+                operands: Vec::new(),
+            });
+            break;
+        }
+
+        let Ok(ix_data_size) = mem.read_code_seg(pc + IX_SIZE_OFFSET) else {
+            break;
+        };
+        let Ok(data) = mem.read_code_seg_slice(pc + IX_DATA_OFFSET, ix_data_size as usize) else {
+            break;
+        };
+        if data.len() < expected_data_len(ix_type) {
+            return Err(DisasmError::InvalidInstruction(opcode));
+        }
+
+        let operands = match ix_type {
+            IxType::NOP | IxType::RET | IxType::RETI => Vec::new(),
+            IxType::MOV => vec![Operand::Reg(decode_reg(data[0])?), Operand::Imm(data[1])],
+            IxType::LDM | IxType::STM | IxType::ADD | IxType::SUB | IxType::MUL => {
+                let addr = ((data[0] as u16) << 8) | data[1] as u16;
+                vec![Operand::Addr(addr), Operand::Reg(decode_reg(data[2])?)]
+            }
+            IxType::DIV => {
+                let addr = ((data[0] as u16) << 8) | data[1] as u16;
+                vec![
+                    Operand::Addr(addr),
+                    Operand::Reg(decode_reg(data[2])?),
+                    Operand::Reg(decode_reg(data[3])?),
+                ]
+            }
+            IxType::JMP | IxType::JNZ | IxType::JZ | IxType::CALL => {
+                let addr = ((data[0] as u16) << 8) | data[1] as u16;
+                vec![Operand::Addr(addr)]
+            }
+            IxType::PUSH | IxType::POP => vec![Operand::Reg(decode_reg(data[0])?)],
+        };
+
+        items.push(DisasmItem {
+            addr: pc,
+            ix_type,
+            operands,
+        });
+
+        pc += IX_META_SIZE + ix_data_size as u16;
+    }
+
+    Ok(items)
+}
+
+// Convenience wrapper around disasm that renders the decoded instructions as
+// a newline-separated listing.
+pub fn disasm_listing(mem: &Memory, start: u16) -> Result<String, DisasmError> {
+    let items = disasm(mem, start)?;
+    let listing = items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    #[test]
+    fn round_trips_through_the_assembler() {
+        let mut mem = Memory::new();
+        asm::assemble_and_load(&mut mem, 0, "mov a, 1\nstm 0x00 0x00, a\nnop\n").unwrap();
+
+        let items = disasm(&mem, 0).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].mnemonic(), "mov");
+        assert_eq!(
+            items[0].operands,
+            vec![Operand::Reg(Register::A), Operand::Imm(1)]
+        );
+        assert_eq!(items[1].mnemonic(), "stm");
+        assert_eq!(
+            items[1].operands,
+            vec![Operand::Addr(0), Operand::Reg(Register::A)]
+        );
+        assert_eq!(items[2].mnemonic(), "nop");
+    }
+
+    #[test]
+    fn unknown_opcode_reports_invalid_instruction_instead_of_panicking() {
+        let mut mem = Memory::new();
+        mem.load_ix(0, &[0xff, 0]).unwrap();
+        assert_eq!(
+            disasm(&mem, 0).unwrap_err(),
+            DisasmError::InvalidInstruction(0xff)
+        );
+    }
+
+    #[test]
+    fn data_size_too_short_for_the_opcode_reports_invalid_instruction() {
+        // `MOV` needs 2 operand bytes but declares a data size of 0.
+        let mut mem = Memory::new();
+        mem.load_ix(0, &[IxType::MOV as u8, 0]).unwrap();
+        assert_eq!(
+            disasm(&mem, 0).unwrap_err(),
+            DisasmError::InvalidInstruction(IxType::MOV as u8)
+        );
+    }
+}
@@ -0,0 +1,5 @@
+pub mod asm;
+pub mod disasm;
+pub mod memory;
+pub mod trap;
+pub mod vm;
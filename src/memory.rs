@@ -1,17 +1,100 @@
+use crate::trap::{Segment, Trap};
+
 pub const CODE_SEG_SIZE: u16 = 0x4000; // 16KB
 pub const DATA_SEG_SIZE: u16 = 0x2000; // 8KB
 pub const STACK_SEG_SIZE: u16 = 0xA000; // 40KB
 
+// Divides the 64KB address space into NUM_PAGES pages of this size.
+pub const PAGE_SIZE: u16 = 0x100; // 256 bytes
+pub const NUM_PAGES: usize = 0x100; // 64KB / 256
+
+pub const PAGE_PRESENT: u8 = 0b01;
+pub const PAGE_WRITABLE: u8 = 0b10;
+
+// A single virtual->physical page mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct PageEntry {
+    pub ppn: u8,
+    pub flags: u8,
+}
+
+// Maps virtual page numbers to physical pages. When a Memory has a page table
+// installed, every read_mem/write_mem access is translated through it instead
+// of indexing the segments directly.
+#[derive(Debug)]
+pub struct PageTable {
+    entries: [Option<PageEntry>; NUM_PAGES],
+}
+
+impl PageTable {
+    fn new() -> PageTable {
+        PageTable {
+            entries: [None; NUM_PAGES],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Memory {
     pub code_seg: [u8; 0x4000],  // 16KB (0x4000 is 16384 in decimal)
     pub data_seg: [u8; 0x2000],  // 8KB (0x2000 is 8192 in decimal)
     pub stack_seg: [u8; 0xA000], // 40KB (0xA000 is 40960 in decimal)
+    // None means accesses map straight onto the flat segment layout; Some
+    // routes them through the page table.
+    pub mmu: Option<PageTable>,
+}
+
+/// Returns the segment a flat address falls in, used to tag faults.
+fn seg_of(addr: u16) -> Segment {
+    if addr < CODE_SEG_SIZE {
+        Segment::Code
+    } else if addr < CODE_SEG_SIZE + DATA_SEG_SIZE {
+        Segment::Data
+    } else {
+        Segment::Stack
+    }
+}
+
+// Flat virtual address of a data-segment-relative address, used by exec_ix so
+// data accesses go through read_mem/write_mem and the MMU (when installed)
+// actually sees them. Checked so an address near the top of u16 raises a
+// MemoryAccessFault instead of silently wrapping before the segment bounds
+// check ever runs.
+pub fn data_vaddr(addr: u16) -> Result<u16, Trap> {
+    CODE_SEG_SIZE.checked_add(addr).ok_or(Trap::MemoryAccessFault {
+        addr,
+        seg: Segment::Data,
+    })
+}
+
+// Flat virtual address of a stack-segment-relative address; see data_vaddr.
+pub fn stack_vaddr(addr: u16) -> Result<u16, Trap> {
+    CODE_SEG_SIZE
+        .checked_add(DATA_SEG_SIZE)
+        .and_then(|base| base.checked_add(addr))
+        .ok_or(Trap::MemoryAccessFault {
+            addr,
+            seg: Segment::Stack,
+        })
+}
+
+pub fn check_illegal_mem_access(addr: u16, mem_size: u16, seg: Segment) -> Result<(), Trap> {
+    // The last addressable byte is `mem_size - 1`, so anything at or beyond
+    // `mem_size` is out of bounds.
+    if addr >= mem_size {
+        return Err(Trap::MemoryAccessFault { addr, seg });
+    }
+    Ok(())
 }
 
-pub fn check_illegal_mem_access(addr: u16, mem_size: u16) -> Result<(), String> {
-    if addr > mem_size {
-        return Err(format!("Invalid address: {:#x}", addr));
+// Checks that the half-open range [addr, addr + len) stays inside mem_size,
+// using checked arithmetic so addr + len can't silently wrap around u16.
+fn check_illegal_mem_range(addr: u16, len: usize, mem_size: u16, seg: Segment) -> Result<(), Trap> {
+    let fault = Trap::MemoryAccessFault { addr, seg };
+    let len = u16::try_from(len).map_err(|_| fault)?;
+    let end = addr.checked_add(len).ok_or(fault)?;
+    if end > mem_size {
+        return Err(fault);
     }
     Ok(())
 }
@@ -22,34 +105,104 @@ impl Memory {
             code_seg: [0; CODE_SEG_SIZE as usize],
             data_seg: [0; DATA_SEG_SIZE as usize],
             stack_seg: [0; STACK_SEG_SIZE as usize],
+            mmu: None,
+        }
+    }
+
+    // Installs or updates a virtual->physical page mapping, creating the page
+    // table (and thereby enabling the MMU) on first use. flags is a bitwise OR
+    // of PAGE_PRESENT and PAGE_WRITABLE.
+    pub fn map_page(&mut self, vpn: u8, ppn: u8, flags: u8) {
+        let table = self.mmu.get_or_insert_with(PageTable::new);
+        table.entries[vpn as usize] = Some(PageEntry { ppn, flags });
+    }
+
+    // Removes the mapping for a virtual page, if any. Later accesses to it
+    // fault until it is mapped again.
+    pub fn unmap_page(&mut self, vpn: u8) {
+        if let Some(table) = &mut self.mmu {
+            table.entries[vpn as usize] = None;
+        }
+    }
+
+    // Translates a virtual address to a physical one through table, raising a
+    // Trap::MemoryAccessFault on a missing page or a write to a read-only page.
+    fn translate(table: &PageTable, vaddr: u16, write: bool) -> Result<u16, Trap> {
+        let vpn = (vaddr / PAGE_SIZE) as usize;
+        let offset = vaddr % PAGE_SIZE;
+        let fault = Trap::MemoryAccessFault {
+            addr: vaddr,
+            seg: seg_of(vaddr),
+        };
+        match table.entries[vpn] {
+            Some(entry) if entry.flags & PAGE_PRESENT != 0 => {
+                if write && entry.flags & PAGE_WRITABLE == 0 {
+                    return Err(fault);
+                }
+                Ok(entry.ppn as u16 * PAGE_SIZE + offset)
+            }
+            _ => Err(fault),
+        }
+    }
+
+    fn phys_read(&self, paddr: u16) -> Result<u8, Trap> {
+        if paddr < CODE_SEG_SIZE {
+            self.read_code_seg(paddr)
+        } else if paddr < CODE_SEG_SIZE + DATA_SEG_SIZE {
+            self.read_data_seg(paddr - CODE_SEG_SIZE)
+        } else {
+            self.read_stack_seg(paddr - CODE_SEG_SIZE - DATA_SEG_SIZE)
         }
     }
 
-    pub fn load_ix(&mut self, from_addr: u16, data: &[u8]) -> Result<usize, String> {
-        if data.len() > CODE_SEG_SIZE as usize {
-            return Err(format!("Data too large to fit in code segment"));
+    fn phys_write(&mut self, paddr: u16, data: u8) -> Result<(), Trap> {
+        if paddr < CODE_SEG_SIZE {
+            self.write_code_seg(data, paddr)
+        } else if paddr < CODE_SEG_SIZE + DATA_SEG_SIZE {
+            self.write_data_seg(data, paddr - CODE_SEG_SIZE)
+        } else {
+            self.write_stack_seg(data, paddr - CODE_SEG_SIZE - DATA_SEG_SIZE)
         }
+    }
+
+    pub fn load_ix(&mut self, from_addr: u16, data: &[u8]) -> Result<usize, Trap> {
+        check_illegal_mem_range(from_addr, data.len(), CODE_SEG_SIZE, Segment::Code)?;
         let start = from_addr as usize;
         let end = start + data.len();
         self.code_seg[start..end].copy_from_slice(data);
         Ok(data.len())
     }
 
-    pub fn write_code_seg(&mut self, data: u8, addr: u16) -> Result<(), String> {
-        check_illegal_mem_access(addr, CODE_SEG_SIZE)?;
+    // Writes several non-contiguous buffers into the code segment starting at
+    // from_addr, in order, returning the total bytes written. The whole region
+    // is bounds-checked up front, so either every buffer is placed or none are.
+    pub fn load_ix_vectored(&mut self, from_addr: u16, bufs: &[&[u8]]) -> Result<usize, Trap> {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        check_illegal_mem_range(from_addr, total, CODE_SEG_SIZE, Segment::Code)?;
+
+        let mut offset = from_addr as usize;
+        for buf in bufs {
+            let end = offset + buf.len();
+            self.code_seg[offset..end].copy_from_slice(buf);
+            offset = end;
+        }
+        Ok(total)
+    }
+
+    pub fn write_code_seg(&mut self, data: u8, addr: u16) -> Result<(), Trap> {
+        check_illegal_mem_access(addr, CODE_SEG_SIZE, Segment::Code)?;
         self.code_seg[addr as usize] = data;
         Ok(())
     }
 
-    pub fn write_data_seg(&mut self, data: u8, addr: u16) -> Result<(), String> {
-        check_illegal_mem_access(addr, DATA_SEG_SIZE)?;
+    pub fn write_data_seg(&mut self, data: u8, addr: u16) -> Result<(), Trap> {
+        check_illegal_mem_access(addr, DATA_SEG_SIZE, Segment::Data)?;
         self.data_seg[addr as usize] = data;
         Ok(())
     }
 
-    pub fn write_data_seg_slice(&mut self, data: &[u8], addr: u16) -> Result<(), String> {
-        check_illegal_mem_access(addr, DATA_SEG_SIZE)?;
-        check_illegal_mem_access(addr + data.len() as u16, DATA_SEG_SIZE)?;
+    pub fn write_data_seg_slice(&mut self, data: &[u8], addr: u16) -> Result<(), Trap> {
+        check_illegal_mem_range(addr, data.len(), DATA_SEG_SIZE, Segment::Data)?;
 
         let start = addr as usize;
         let end = start + data.len();
@@ -57,42 +210,134 @@ impl Memory {
         Ok(())
     }
 
-    pub fn write_stack_seg(&mut self, data: u8, addr: u16) -> Result<(), String> {
-        check_illegal_mem_access(addr, STACK_SEG_SIZE)?;
+    pub fn write_stack_seg(&mut self, data: u8, addr: u16) -> Result<(), Trap> {
+        check_illegal_mem_access(addr, STACK_SEG_SIZE, Segment::Stack)?;
         self.stack_seg[addr as usize] = data;
         Ok(())
     }
 
-    pub fn read_code_seg(&self, addr: u16) -> Result<u8, String> {
-        check_illegal_mem_access(addr, CODE_SEG_SIZE)?;
+    pub fn read_code_seg(&self, addr: u16) -> Result<u8, Trap> {
+        check_illegal_mem_access(addr, CODE_SEG_SIZE, Segment::Code)?;
 
         Ok(self.code_seg[addr as usize])
     }
 
-    pub fn read_code_seg_slice(&self, addr: u16, size: usize) -> Result<&[u8], String> {
-        check_illegal_mem_access(addr as u16, CODE_SEG_SIZE)?;
-        check_illegal_mem_access(addr as u16 + size as u16, CODE_SEG_SIZE)?;
+    pub fn read_code_seg_slice(&self, addr: u16, size: usize) -> Result<&[u8], Trap> {
+        check_illegal_mem_range(addr, size, CODE_SEG_SIZE, Segment::Code)?;
 
         Ok(&self.code_seg[addr as usize..addr as usize + size])
     }
 
-    pub fn read_data_seg(&self, addr: u16) -> Result<u8, String> {
-        check_illegal_mem_access(addr, DATA_SEG_SIZE)?;
+    pub fn read_data_seg(&self, addr: u16) -> Result<u8, Trap> {
+        check_illegal_mem_access(addr, DATA_SEG_SIZE, Segment::Data)?;
         Ok(self.data_seg[addr as usize])
     }
 
-    pub fn read_stack_seg(&self, addr: u16) -> Result<u8, String> {
-        check_illegal_mem_access(addr, STACK_SEG_SIZE)?;
+    pub fn read_stack_seg(&self, addr: u16) -> Result<u8, Trap> {
+        check_illegal_mem_access(addr, STACK_SEG_SIZE, Segment::Stack)?;
         Ok(self.stack_seg[addr as usize])
     }
 
-    pub fn read_mem(&self, addr: u16) -> Result<u8, String> {
-        if addr < CODE_SEG_SIZE {
-            self.read_code_seg(addr)
-        } else if addr < CODE_SEG_SIZE + DATA_SEG_SIZE {
-            self.read_data_seg(addr - CODE_SEG_SIZE)
-        } else {
-            self.read_stack_seg(addr - CODE_SEG_SIZE - DATA_SEG_SIZE)
-        }
+    pub fn read_mem(&self, addr: u16) -> Result<u8, Trap> {
+        let paddr = match &self.mmu {
+            Some(table) => Self::translate(table, addr, false)?,
+            None => addr,
+        };
+        self.phys_read(paddr)
+    }
+
+    pub fn write_mem(&mut self, addr: u16, data: u8) -> Result<(), Trap> {
+        let paddr = match &self.mmu {
+            Some(table) => Self::translate(table, addr, true)?,
+            None => addr,
+        };
+        self.phys_write(paddr, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_vaddr_faults_instead_of_overflowing() {
+        assert_eq!(
+            data_vaddr(u16::MAX),
+            Err(Trap::MemoryAccessFault {
+                addr: u16::MAX,
+                seg: Segment::Data,
+            })
+        );
+    }
+
+    #[test]
+    fn stack_vaddr_faults_instead_of_overflowing() {
+        assert_eq!(
+            stack_vaddr(u16::MAX),
+            Err(Trap::MemoryAccessFault {
+                addr: u16::MAX,
+                seg: Segment::Stack,
+            })
+        );
+    }
+
+    #[test]
+    fn map_page_redirects_accesses_to_the_physical_page() {
+        let mut mem = Memory::new();
+        // Virtual page 0 onto physical page 1 of the code segment.
+        mem.map_page(0, 1, PAGE_PRESENT | PAGE_WRITABLE);
+        mem.write_mem(0, 0xab).unwrap();
+        assert_eq!(mem.code_seg[PAGE_SIZE as usize], 0xab);
+        assert_eq!(mem.read_mem(0).unwrap(), 0xab);
+    }
+
+    #[test]
+    fn unmapped_page_faults() {
+        let mut mem = Memory::new();
+        mem.map_page(0, 0, PAGE_PRESENT);
+        assert!(mem.read_mem(PAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn write_to_read_only_page_faults() {
+        let mut mem = Memory::new();
+        mem.map_page(0, 0, PAGE_PRESENT);
+        assert!(mem.write_mem(0, 1).is_err());
+        assert!(mem.read_mem(0).is_ok());
+    }
+
+    #[test]
+    fn unmap_page_faults_subsequent_accesses() {
+        let mut mem = Memory::new();
+        mem.map_page(0, 0, PAGE_PRESENT);
+        mem.read_mem(0).unwrap();
+        mem.unmap_page(0);
+        assert!(mem.read_mem(0).is_err());
+    }
+
+    #[test]
+    fn load_ix_vectored_writes_buffers_in_order() {
+        let mut mem = Memory::new();
+        let bufs: Vec<&[u8]> = vec![&[1, 2], &[3, 4, 5]];
+        let written = mem.load_ix_vectored(10, &bufs).unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(&mem.code_seg[10..15], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn load_ix_vectored_rejects_the_whole_write_if_the_total_does_not_fit() {
+        let mut mem = Memory::new();
+        let all_of_it = vec![0xab; CODE_SEG_SIZE as usize];
+        let bufs: Vec<&[u8]> = vec![&all_of_it, &[0xcd]]; // one byte over capacity
+        let err = mem.load_ix_vectored(0, &bufs).unwrap_err();
+        assert_eq!(
+            err,
+            Trap::MemoryAccessFault {
+                addr: 0,
+                seg: Segment::Code,
+            }
+        );
+        // Not even the first, individually in-bounds buffer was written.
+        assert_eq!(mem.code_seg[0], 0);
     }
 }
@@ -0,0 +1,26 @@
+// The memory region a faulting access was directed at.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Code,
+    Data,
+    Stack,
+}
+
+// A structured fault raised while decoding or executing an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    InvalidInstruction(u8),
+    InvalidRegister(u8),
+    MemoryAccessFault { addr: u16, seg: Segment },
+    DivideByZero,
+    Halt,
+}
+
+// What an embedder's trap handler asks the VM to do once it has inspected a
+// Trap: carry on from where the fault occurred, or stop the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapAction {
+    Resume,
+    Abort,
+}
@@ -4,9 +4,10 @@ use std::{
 };
 
 use crate::memory::{self, Memory};
+use crate::trap::{Trap, TrapAction};
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
     A,
     B,
@@ -19,10 +20,13 @@ pub enum Register {
     SP,
     PC,
     DP,
+    // Status flags set by arithmetic ops and consumed by conditional jumps.
+    // See `FLAG_ZERO`, `FLAG_CARRY` and `FLAG_OVERFLOW`.
+    Flags,
 }
 
 impl TryFrom<u8> for Register {
-    type Error = String;
+    type Error = Trap;
 
     fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
@@ -37,7 +41,8 @@ impl TryFrom<u8> for Register {
             8 => Ok(Register::SP),
             9 => Ok(Register::PC),
             10 => Ok(Register::DP),
-            _ => Err(format!("Invalid register: {:#x}", val)),
+            11 => Ok(Register::Flags),
+            _ => Err(Trap::InvalidRegister(val)),
         }
     }
 }
@@ -56,8 +61,23 @@ pub enum IxType {
     LDM,
     STM,
     ADD,
+    SUB,
+    MUL,
+    DIV,
+    JMP,
+    JNZ,
+    JZ,
+    PUSH,
+    POP,
+    CALL,
+    RET,
+    RETI,
 }
 
+pub const FLAG_ZERO: u16 = 0b001;
+pub const FLAG_CARRY: u16 = 0b010;
+pub const FLAG_OVERFLOW: u16 = 0b100;
+
 pub const IX_SIZE_OFFSET: u16 = 1;
 
 pub const IX_META_SIZE: u16 = 2;
@@ -67,7 +87,7 @@ pub const IX_DATA_OFFSET: u16 = 2;
 pub const CONCURRENT_THREADS: u8 = 10;
 
 impl TryFrom<u8> for IxType {
-    type Error = String;
+    type Error = Trap;
 
     fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
@@ -76,7 +96,18 @@ impl TryFrom<u8> for IxType {
             2 => Ok(IxType::LDM),
             3 => Ok(IxType::STM),
             4 => Ok(IxType::ADD),
-            _ => Err(format!("Invalid instruction: {:#x}", val)),
+            5 => Ok(IxType::SUB),
+            6 => Ok(IxType::MUL),
+            7 => Ok(IxType::DIV),
+            8 => Ok(IxType::JMP),
+            9 => Ok(IxType::JNZ),
+            10 => Ok(IxType::JZ),
+            11 => Ok(IxType::PUSH),
+            12 => Ok(IxType::POP),
+            13 => Ok(IxType::CALL),
+            14 => Ok(IxType::RET),
+            15 => Ok(IxType::RETI),
+            _ => Err(Trap::InvalidInstruction(val)),
         }
     }
 }
@@ -87,30 +118,210 @@ fn get_addr_from_two_bytes(high: u8, low: u8) -> u16 {
     high | low
 }
 
-type RegisterArray = [u16; 10];
+fn write_flags(regs: &mut RegisterArray, zero: bool, carry: bool, overflow: bool) {
+    let mut flags = 0u16;
+    if zero {
+        flags |= FLAG_ZERO;
+    }
+    if carry {
+        flags |= FLAG_CARRY;
+    }
+    if overflow {
+        flags |= FLAG_OVERFLOW;
+    }
+    regs[Register::Flags.into_usize()] = flags;
+}
+
+fn signed_overflow_add(a: u16, b: u16, res: u16) -> bool {
+    // Operands share a sign that differs from the result's sign.
+    ((a ^ res) & (b ^ res) & 0x8000) != 0
+}
+
+fn signed_overflow_sub(a: u16, b: u16, res: u16) -> bool {
+    // Operands differ in sign and the result's sign differs from the minuend's.
+    ((a ^ b) & (a ^ res) & 0x8000) != 0
+}
+
+// A register or data-segment location touched by an instruction, used by the
+// concurrent engine to reason about conflicts between instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resource {
+    Reg(usize),
+    Data(u16),
+}
+
+struct DecodedIx {
+    ix: Ix,
+    pc: u16,
+    reads: Vec<Resource>,
+    writes: Vec<Resource>,
+}
+
+// Whether an instruction redirects control flow and therefore ends the
+// statically-analysable straight-line region.
+fn is_branch(ix_type: IxType) -> bool {
+    matches!(
+        ix_type,
+        IxType::JMP | IxType::JNZ | IxType::JZ | IxType::CALL | IxType::RET | IxType::RETI
+    )
+}
+
+// Computes the register and data-segment read/write sets of a (non-branch)
+// instruction. Stack instructions model the stack through `SP`, which both
+// serializes them and captures their effect on memory.
+fn access_sets(ix: &Ix) -> (Vec<Resource>, Vec<Resource>) {
+    let data = &ix.ix_data;
+    let flags = Resource::Reg(Register::Flags.into_usize());
+    let sp = Resource::Reg(Register::SP.into_usize());
+
+    match ix.ix_type {
+        IxType::NOP => (Vec::new(), Vec::new()),
+        IxType::MOV => (Vec::new(), vec![Resource::Reg(data[0] as usize)]),
+        IxType::LDM => {
+            let addr = get_addr_from_two_bytes(data[0], data[1]);
+            (vec![Resource::Data(addr)], vec![Resource::Reg(data[2] as usize)])
+        }
+        IxType::STM => {
+            let addr = get_addr_from_two_bytes(data[0], data[1]);
+            (vec![Resource::Reg(data[2] as usize)], vec![Resource::Data(addr)])
+        }
+        IxType::ADD | IxType::SUB | IxType::MUL => {
+            let addr = get_addr_from_two_bytes(data[0], data[1]);
+            let reg = Resource::Reg(data[2] as usize);
+            (vec![Resource::Data(addr), reg], vec![reg, flags])
+        }
+        IxType::DIV => {
+            let addr = get_addr_from_two_bytes(data[0], data[1]);
+            let reg_q = Resource::Reg(data[2] as usize);
+            let reg_r = Resource::Reg(data[3] as usize);
+            (vec![Resource::Data(addr), reg_q], vec![reg_q, reg_r, flags])
+        }
+        IxType::PUSH => (vec![Resource::Reg(data[0] as usize), sp], vec![sp]),
+        IxType::POP => (vec![sp], vec![Resource::Reg(data[0] as usize), sp]),
+        // Branch instructions never enter a parallel region.
+        IxType::JMP | IxType::JNZ | IxType::JZ | IxType::CALL | IxType::RET | IxType::RETI => {
+            (Vec::new(), Vec::new())
+        }
+    }
+}
+
+fn intersects(a: &[Resource], b: &[Resource]) -> bool {
+    a.iter().any(|r| b.contains(r))
+}
+
+// Greedily groups instructions, in program order, into batches whose members
+// pairwise share no write-write or read-write dependency. Because each new
+// instruction is checked against the batch's accumulated read/write sets, the
+// resulting batches are safe to execute in parallel, serialized between them.
+fn batch_instructions(region: &[DecodedIx]) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    let mut reads: Vec<Resource> = Vec::new();
+    let mut writes: Vec<Resource> = Vec::new();
+
+    for (idx, decoded) in region.iter().enumerate() {
+        let conflicts = intersects(&decoded.writes, &reads)
+            || intersects(&decoded.writes, &writes)
+            || intersects(&decoded.reads, &writes);
+
+        if conflicts && !current.is_empty() {
+            batches.push(std::mem::take(&mut current));
+            reads.clear();
+            writes.clear();
+        }
+
+        current.push(idx);
+        reads.extend_from_slice(&decoded.reads);
+        writes.extend_from_slice(&decoded.writes);
+    }
+
+    if !current.is_empty() {
+        batches.push(current);
+    }
+
+    batches
+}
+
+type RegisterArray = [u16; 12];
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ix {
     pub ix_type: IxType,
     pub ix_data_size: u8,
     pub ix_data: Vec<u8>,
 }
 
-#[derive(Debug)]
+// A programmable down-counter that wraps at a fixed period and fires the
+// timer interrupt on each wrap.
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    pub period: u16,
+    pub counter: u16,
+}
+
+// Embedder hook invoked on every `Trap`. Returning `TrapAction::Resume`
+// retries the faulting instruction (e.g. after a page-fault handler has
+// mapped the missing page), while `TrapAction::Abort` unwinds the run. In a
+// parallel batch of `parse_and_exec_ixs_concurrent` the faulting
+// instruction is not retried; see there.
+pub type TrapHandler = Box<dyn FnMut(&mut VM, Trap) -> TrapAction>;
 
 pub struct VM {
     pub registers: Arc<RwLock<RegisterArray>>,
     pub memory: Arc<RwLock<Memory>>,
+    // When unset, every trap aborts.
+    pub trap_handler: Option<TrapHandler>,
+    pub cycles: u64,
+    pub timer: Option<Timer>,
+    // Address jumped to when the timer interrupt fires.
+    pub interrupt_vector: u16,
+    // Set while a timer interrupt is being serviced, which suppresses further
+    // timer interrupts until the handler returns with `RETI`.
+    pub in_interrupt: bool,
+}
+
+pub fn ix_cost(ix: IxType) -> u64 {
+    match ix {
+        IxType::MUL | IxType::DIV => 4,
+        IxType::CALL | IxType::RET | IxType::RETI => 2,
+        _ => 1,
+    }
 }
 
 impl VM {
     pub fn new() -> VM {
         VM {
-            registers: Arc::new(RwLock::new([0; 10])),
+            registers: Arc::new(RwLock::new([0; 12])),
             memory: Arc::new(RwLock::new(Memory::new())),
+            trap_handler: None,
+            cycles: 0,
+            timer: None,
+            interrupt_vector: 0,
+            in_interrupt: false,
         }
     }
 
+    // Arms the wrap-around timer so it fires the timer interrupt every
+    // `period` executed instructions, whatever their `ix_cost`.
+    pub fn set_timer(&mut self, period: u16) {
+        self.timer = Some(Timer {
+            period,
+            counter: period,
+        });
+    }
+
+    pub fn set_interrupt_vector(&mut self, addr: u16) {
+        self.interrupt_vector = addr;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_trap_handler(&mut self, handler: TrapHandler) {
+        self.trap_handler = Some(handler);
+    }
+
     pub fn inc_reg(&mut self, reg: Register, inc_by: u16) {
         let mut registers_write_lock = self.registers.write().unwrap();
         registers_write_lock[reg.into_usize()] += inc_by as u16;
@@ -128,19 +339,50 @@ impl VM {
         );
     }
 
-    pub fn parseand_exec_ixs_seq(&mut self) -> Result<(), String> {
-        let mem_cpy = Arc::clone(&self.memory);
+    // Runs a trap through the registered handler, defaulting to
+    // `TrapAction::Abort` when no handler is installed. The handler is taken
+    // out for the duration of the call so it can borrow `&mut self`.
+    fn dispatch_trap(&mut self, trap: Trap) -> TrapAction {
+        match self.trap_handler.take() {
+            Some(mut handler) => {
+                let action = handler(self, trap);
+                self.trap_handler = Some(handler);
+                action
+            }
+            None => TrapAction::Abort,
+        }
+    }
+
+    pub fn parseand_exec_ixs_seq(&mut self) -> Result<(), Trap> {
+        loop {
+            match self.step_seq() {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(trap) => match self.dispatch_trap(trap) {
+                    TrapAction::Resume => continue,
+                    TrapAction::Abort => return Err(trap),
+                },
+            }
+        }
 
+        Ok(())
+    }
+
+    // Decodes and executes the instruction at the current `PC`. Returns
+    // `Ok(false)` once a `NOP` marks the end of the program, `Ok(true)` after
+    // a normal instruction.
+    fn step_seq(&mut self) -> Result<bool, Trap> {
+        let mem_cpy = Arc::clone(&self.memory);
         let reg_cpy = Arc::clone(&self.registers);
 
-        loop {
-            let reg_read_lock = reg_cpy.read().map_err(|e| e.to_string())?;
+        let inx = {
+            let reg_read_lock = reg_cpy.read().unwrap();
             let pc = reg_read_lock[Register::PC.into_usize()];
-            let mem_read_lock = mem_cpy.read().map_err(|e| e.to_string())?;
+            let mem_read_lock = mem_cpy.read().unwrap();
             let ix = mem_read_lock.read_code_seg(pc)?;
 
             if ix == IxType::NOP as u8 {
-                break;
+                return Ok(false);
             }
 
             let ix_data_size = mem_read_lock.read_code_seg(pc + IX_SIZE_OFFSET)?;
@@ -148,95 +390,208 @@ impl VM {
             let ix_data =
                 mem_read_lock.read_code_seg_slice(pc + IX_DATA_OFFSET, ix_data_size as usize)?;
 
-            let inx = Ix {
+            Ix {
                 ix_type: IxType::try_from(ix)?,
                 ix_data_size,
                 ix_data: ix_data.to_vec(),
-            };
+            }
+        };
 
-            drop(mem_read_lock);
-            drop(reg_read_lock);
+        let ix_type = inx.ix_type;
+        Self::exec_ix(mem_cpy, reg_cpy, inx)?;
 
-            Self::exec_ix(Arc::clone(&mem_cpy), Arc::clone(&reg_cpy), inx)?;
+        self.cycles = self.cycles.wrapping_add(ix_cost(ix_type));
+
+        // `RETI` returns from a timer interrupt, so re-enable further
+        // interrupts before the timer is ticked again.
+        if let IxType::RETI = ix_type {
+            self.in_interrupt = false;
         }
 
-        Ok(())
+        if self.tick_timer() {
+            self.enter_interrupt()?;
+        }
+
+        Ok(true)
     }
 
-    pub fn parse_and_exec_ixs_concurrent(&mut self) -> Result<(), String> {
-        let mem_cpy = Arc::clone(&self.memory);
-        let reg_cpy = Arc::clone(&self.registers);
+    // Advances the timer by one step per executed instruction (not by the
+    // instruction's `ix_cost`), returning `true` when it wraps and an
+    // interrupt should be dispatched. Suppressed while one is already being
+    // serviced.
+    fn tick_timer(&mut self) -> bool {
+        if self.in_interrupt {
+            return false;
+        }
+        let Some(timer) = &mut self.timer else {
+            return false;
+        };
+        if timer.period == 0 {
+            return false;
+        }
+        timer.counter = timer.counter.wrapping_sub(1);
+        if timer.counter == 0 {
+            timer.counter = timer.period;
+            true
+        } else {
+            false
+        }
+    }
 
-        let mut threads: Vec<JoinHandle<Result<(), String>>> = Vec::new();
+    // Saves `PC` to the stack and jumps to the interrupt vector, as if by a
+    // hardware-dispatched `CALL`. The matching `RETI` restores `PC`.
+    fn enter_interrupt(&mut self) -> Result<(), Trap> {
+        let mut reg_write_lock = self.registers.write().unwrap();
+        let pc = reg_write_lock[Register::PC.into_usize()];
+        let sp = reg_write_lock[Register::SP.into_usize()];
+        {
+            let mut mem_write_lock = self.memory.write().unwrap();
+            mem_write_lock.write_mem(memory::stack_vaddr(sp)?, (pc >> 8) as u8)?;
+            mem_write_lock.write_mem(memory::stack_vaddr(sp.wrapping_add(1))?, (pc & 0xff) as u8)?;
+        }
+        reg_write_lock[Register::SP.into_usize()] = sp.wrapping_add(2);
+        reg_write_lock[Register::PC.into_usize()] = self.interrupt_vector;
+        self.in_interrupt = true;
+        Ok(())
+    }
 
-        let mut ixs_count = 0;
+    // Executes the program with a dependency-aware parallel engine: a single
+    // decode pass builds the straight-line run of instructions starting at
+    // the current `PC`, groups them into batches with no write-write or
+    // read-write overlap, and runs each batch in parallel, serialized against
+    // the next. Decoding stops at the first branch, and the remainder (loops,
+    // conditionals, the terminating `NOP`) falls back to
+    // `parseand_exec_ixs_seq`.
+    //
+    // A batch runs as one block with no per-instruction timer ticks, so with
+    // a timer armed it could run a region to completion that `exec_seq` would
+    // have interrupted partway through. An armed timer defers the whole
+    // region to the sequential executor instead, which is the only one that
+    // ticks the timer and can dispatch an interrupt mid-region.
+    //
+    // Both the decode pass and the batch threads route their traps through
+    // `dispatch_trap`, the same embedder hook `parseand_exec_ixs_seq` uses.
+    // After `TrapAction::Resume` a decode-pass trap is retried by decoding
+    // the region again. A trap raised inside a batch thread is not re-run:
+    // execution carries on with the rest of the region and `PC` still moves
+    // past it, so that instruction's effect is skipped.
+    pub fn parse_and_exec_ixs_concurrent(&mut self) -> Result<(), Trap> {
+        if self.timer.is_some() {
+            return self.parseand_exec_ixs_seq();
+        }
 
-        let mut ix_pointer = {
-            let reg_read_lock = reg_cpy.read().map_err(|e| e.to_string())?;
-            let pc = reg_read_lock[Register::PC.into_usize()];
-            pc
+        let start_pc = self.registers.read().unwrap()[Register::PC.into_usize()];
+        let region = loop {
+            match self.decode_parallel_region(start_pc) {
+                Ok(region) => break region,
+                Err(trap) => match self.dispatch_trap(trap) {
+                    TrapAction::Resume => continue,
+                    TrapAction::Abort => return Err(trap),
+                },
+            }
         };
 
-        loop {
-            let mem_read_lock = mem_cpy.read().map_err(|e| e.to_string())?;
-            let ix = mem_read_lock.read_code_seg(ix_pointer)?;
-            let ix_data_size = mem_read_lock.read_code_seg(ix_pointer + IX_SIZE_OFFSET)?;
+        if let Some(last) = region.last() {
+            let region_end = last.pc + last.ix.ix_data_size as u16 + IX_META_SIZE;
+
+            for batch in batch_instructions(&region) {
+                let mut threads: Vec<JoinHandle<Result<(), Trap>>> = Vec::new();
+                for idx in batch {
+                    let decoded = &region[idx];
+                    let ix = decoded.ix.clone();
+                    let pc = decoded.pc;
+                    let mem_cpy = Arc::clone(&self.memory);
+                    let reg_cpy = Arc::clone(&self.registers);
+                    threads.push(thread::spawn(move || -> Result<(), Trap> {
+                        Self::apply_ix(mem_cpy, reg_cpy, ix, pc).map(|_| ())
+                    }));
+                }
+                for thread in threads {
+                    if let Err(trap) = thread.join().unwrap() {
+                        match self.dispatch_trap(trap) {
+                            TrapAction::Resume => continue,
+                            TrapAction::Abort => return Err(trap),
+                        }
+                    }
+                }
+            }
 
-            if ix == IxType::NOP as u8 {
-                break;
+            for decoded in &region {
+                self.cycles = self.cycles.wrapping_add(ix_cost(decoded.ix.ix_type));
             }
-            ixs_count += 1;
 
-            ix_pointer += ix_data_size as u16 + IX_META_SIZE;
-            drop(mem_read_lock);
+            self.registers.write().unwrap()[Register::PC.into_usize()] = region_end;
         }
 
-        for _ in 0..(ixs_count / 10) {
-            let mem_cpy = Arc::clone(&mem_cpy);
-            let reg_cpy = Arc::clone(&reg_cpy);
-
-            threads.push(thread::spawn(move || -> Result<(), String> {
-                for _ in 0..10 {
-                    let mem_read_lock = mem_cpy.read().map_err(|e| e.to_string())?;
-                    let reg_read_lock = reg_cpy.read().map_err(|e| e.to_string())?;
-
-                    let pc = reg_read_lock[Register::PC.into_usize()];
-
-                    let ix = mem_read_lock.read_code_seg(pc)?;
-
-                    let ix_data_size = mem_read_lock.read_code_seg(pc + IX_SIZE_OFFSET)?;
+        // Everything from the first branch onward runs sequentially.
+        self.parseand_exec_ixs_seq()
+    }
 
-                    let ix_data = mem_read_lock
-                        .read_code_seg_slice(pc + IX_DATA_OFFSET, ix_data_size as usize)?;
+    // Decodes the straight-line, branch-free run of instructions starting at
+    // `pc`, recording each instruction's address and its read/write sets.
+    // Stops at the first control-flow instruction, at a `NOP`, or at the end
+    // of the code segment.
+    fn decode_parallel_region(&self, mut pc: u16) -> Result<Vec<DecodedIx>, Trap> {
+        let mem_cpy = Arc::clone(&self.memory);
+        let mut region = Vec::new();
 
-                    let inx = Ix {
-                        ix_type: IxType::try_from(ix)?,
-                        ix_data_size,
-                        ix_data: ix_data.to_vec(),
-                    };
-                    drop(mem_read_lock);
-                    drop(reg_read_lock);
+        loop {
+            let mem_read_lock = mem_cpy.read().unwrap();
+            let Ok(opcode) = mem_read_lock.read_code_seg(pc) else {
+                break;
+            };
+            if opcode == IxType::NOP as u8 {
+                break;
+            }
+            let ix_type = IxType::try_from(opcode)?;
+            if is_branch(ix_type) {
+                break;
+            }
 
-                    Self::exec_ix(Arc::clone(&mem_cpy), Arc::clone(&reg_cpy), inx)?;
-                }
+            let ix_data_size = mem_read_lock.read_code_seg(pc + IX_SIZE_OFFSET)?;
+            let ix_data =
+                mem_read_lock.read_code_seg_slice(pc + IX_DATA_OFFSET, ix_data_size as usize)?;
+            let ix = Ix {
+                ix_type,
+                ix_data_size,
+                ix_data: ix_data.to_vec(),
+            };
+            drop(mem_read_lock);
 
-                Ok(())
-            }))
+            let (reads, writes) = access_sets(&ix);
+            region.push(DecodedIx {
+                ix,
+                pc,
+                reads,
+                writes,
+            });
+            pc += ix_data_size as u16 + IX_META_SIZE;
         }
 
-        println!("threads: {:?}", threads.len());
-        for thread in threads {
-            thread.join().map_err(|_| "Thread panicked".to_string())??;
-        }
+        Ok(region)
+    }
 
+    pub fn exec_ix(
+        mem_cpy: Arc<RwLock<Memory>>,
+        reg_cpy: Arc<RwLock<RegisterArray>>,
+        inx: Ix,
+    ) -> Result<(), Trap> {
+        let pc = reg_cpy.read().unwrap()[Register::PC.into_usize()];
+        let next_pc = Self::apply_ix(Arc::clone(&mem_cpy), Arc::clone(&reg_cpy), inx, pc)?;
+        reg_cpy.write().unwrap()[Register::PC.into_usize()] = next_pc;
         Ok(())
     }
 
-    pub fn exec_ix(
+    // Applies an instruction's register/memory effect and returns the `PC`
+    // the sequential executor should continue from. Unlike `exec_ix` it does
+    // not itself write `PC`, so the concurrent engine can apply independent
+    // instructions in parallel using each instruction's own decoded address.
+    fn apply_ix(
         mem_cpy: Arc<RwLock<Memory>>,
         reg_cpy: Arc<RwLock<RegisterArray>>,
         inx: Ix,
-    ) -> Result<(), String> {
+        pc: u16,
+    ) -> Result<u16, Trap> {
         let Ix {
             ix_type,
             ix_data_size,
@@ -245,59 +600,385 @@ impl VM {
 
         Self::print_ix(ix_type, ix_data_size, &ix_data);
 
+        // Default fall-through target; branch instructions overwrite it.
+        let mut next_pc = pc.wrapping_add(ix_data_size as u16 + IX_META_SIZE);
+
         match ix_type {
             IxType::NOP => {
                 // do nothing
             }
             IxType::MOV => {
                 let reg = Register::try_from(ix_data[0])?;
-                let mut reg_write_lock = reg_cpy.write().map_err(|e| e.to_string())?;
+                let mut reg_write_lock = reg_cpy.write().unwrap();
                 reg_write_lock[reg.into_usize()] = ix_data[1] as u16;
                 drop(reg_write_lock);
             }
             IxType::LDM => {
                 let addr = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
-                let mem_read_lock = mem_cpy.read().map_err(|e| e.to_string())?;
-                let data = mem_read_lock.read_data_seg(addr)?;
+                let mem_read_lock = mem_cpy.read().unwrap();
+                let data = mem_read_lock.read_mem(memory::data_vaddr(addr)?)?;
                 let reg = Register::try_from(ix_data[2])?;
                 let mut reg_write_lock = reg_cpy.write().unwrap();
                 reg_write_lock[reg.into_usize()] = data as u16;
             }
             IxType::STM => {
                 let addr = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
-                let reg_read_lock = reg_cpy.read().map_err(|e| e.to_string())?;
+                // Copy the registers out so the read lock is released before
+                // the memory write lock is taken (LDM locks in the other order).
+                let reg_read_lock = *reg_cpy.read().unwrap();
                 let reg_val = reg_read_lock[ix_data[2] as usize] as u8;
                 mem_cpy
                     .write()
                     .unwrap()
-                    .write_data_seg(reg_val as u8, addr)?;
+                    .write_mem(memory::data_vaddr(addr)?, reg_val)?;
             }
-            IxType::ADD => {
+            IxType::ADD | IxType::SUB | IxType::MUL => {
                 let addr = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
                 let reg = Register::try_from(ix_data[2])?;
-                let reg_read_lock = reg_cpy.read().map_err(|e| e.to_string())?;
-                let reg_val = reg_read_lock[reg.into_usize()];
+                let reg_val = reg_cpy.read().unwrap()[reg.into_usize()];
+
+                let data = mem_cpy.read().unwrap().read_mem(memory::data_vaddr(addr)?)? as u16;
+                let (res, carry, overflow) = match ix_type {
+                    IxType::ADD => {
+                        let (res, carry) = reg_val.overflowing_add(data);
+                        (res, carry, signed_overflow_add(reg_val, data, res))
+                    }
+                    IxType::SUB => {
+                        let (res, carry) = reg_val.overflowing_sub(data);
+                        (res, carry, signed_overflow_sub(reg_val, data, res))
+                    }
+                    _ => {
+                        let (res, carry) = reg_val.overflowing_mul(data);
+                        (res, carry, carry)
+                    }
+                };
 
-                let mem_read_lock = mem_cpy.read().map_err(|e| e.to_string())?;
-                let data = mem_read_lock.read_data_seg(addr)?;
                 let mut reg_write_lock = reg_cpy.write().unwrap();
-                reg_write_lock[reg.into_usize()] = reg_val.wrapping_add(data as u16);
+                reg_write_lock[reg.into_usize()] = res;
+                write_flags(&mut reg_write_lock, res == 0, carry, overflow);
             }
-        }
+            IxType::DIV => {
+                // Dividend in the first register, divisor in the data segment;
+                // quotient goes back to the first register and the remainder to
+                // the second.
+                let addr = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
+                let reg_q = Register::try_from(ix_data[2])?;
+                let reg_r = Register::try_from(ix_data[3])?;
 
-        let mut reg_write_lock = reg_cpy.write().unwrap();
-        reg_write_lock[Register::PC.into_usize()] += ix_data_size as u16 + IX_META_SIZE;
+                let dividend = reg_cpy.read().unwrap()[reg_q.into_usize()];
+                let divisor = mem_cpy.read().unwrap().read_mem(memory::data_vaddr(addr)?)? as u16;
+                if divisor == 0 {
+                    return Err(Trap::DivideByZero);
+                }
 
-        Ok(())
+                let quotient = dividend / divisor;
+                let remainder = dividend % divisor;
+                let mut reg_write_lock = reg_cpy.write().unwrap();
+                reg_write_lock[reg_q.into_usize()] = quotient;
+                reg_write_lock[reg_r.into_usize()] = remainder;
+                write_flags(&mut reg_write_lock, quotient == 0, false, false);
+            }
+            IxType::JMP => {
+                next_pc = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
+            }
+            IxType::JNZ => {
+                if reg_cpy.read().unwrap()[Register::Flags.into_usize()] & FLAG_ZERO == 0 {
+                    next_pc = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
+                }
+            }
+            IxType::JZ => {
+                if reg_cpy.read().unwrap()[Register::Flags.into_usize()] & FLAG_ZERO != 0 {
+                    next_pc = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
+                }
+            }
+            IxType::PUSH => {
+                let reg = Register::try_from(ix_data[0])?;
+                let val = reg_cpy.read().unwrap()[reg.into_usize()] as u8;
+                let sp = reg_cpy.read().unwrap()[Register::SP.into_usize()];
+                mem_cpy
+                    .write()
+                    .unwrap()
+                    .write_mem(memory::stack_vaddr(sp)?, val)?;
+                reg_cpy.write().unwrap()[Register::SP.into_usize()] = sp.wrapping_add(1);
+            }
+            IxType::POP => {
+                let reg = Register::try_from(ix_data[0])?;
+                let sp = reg_cpy.read().unwrap()[Register::SP.into_usize()];
+                let new_sp = sp.wrapping_sub(1);
+                let val = mem_cpy.read().unwrap().read_mem(memory::stack_vaddr(new_sp)?)?;
+                let mut reg_write_lock = reg_cpy.write().unwrap();
+                reg_write_lock[reg.into_usize()] = val as u16;
+                reg_write_lock[Register::SP.into_usize()] = new_sp;
+            }
+            IxType::CALL => {
+                // Push the return address (high byte first) then jump.
+                let target = get_addr_from_two_bytes(ix_data[0], ix_data[1]);
+                let sp = reg_cpy.read().unwrap()[Register::SP.into_usize()];
+                {
+                    let mut mem_write_lock = mem_cpy.write().unwrap();
+                    mem_write_lock.write_mem(memory::stack_vaddr(sp)?, (next_pc >> 8) as u8)?;
+                    mem_write_lock.write_mem(
+                        memory::stack_vaddr(sp.wrapping_add(1))?,
+                        (next_pc & 0xff) as u8,
+                    )?;
+                }
+                reg_cpy.write().unwrap()[Register::SP.into_usize()] = sp.wrapping_add(2);
+                next_pc = target;
+            }
+            IxType::RET | IxType::RETI => {
+                // RETI additionally re-enables timer interrupts once the
+                // sequential loop observes it (see `step_seq`).
+                let sp = reg_cpy.read().unwrap()[Register::SP.into_usize()];
+                let new_sp = sp.wrapping_sub(2);
+                let (high, low) = {
+                    let mem_read_lock = mem_cpy.read().unwrap();
+                    let high = mem_read_lock.read_mem(memory::stack_vaddr(new_sp)?)?;
+                    let low = mem_read_lock.read_mem(memory::stack_vaddr(new_sp.wrapping_add(1))?)?;
+                    (high, low)
+                };
+                reg_cpy.write().unwrap()[Register::SP.into_usize()] = new_sp;
+                next_pc = get_addr_from_two_bytes(high, low);
+            }
+        }
+
+        Ok(next_pc)
     }
 
-    pub fn exec_seq(&mut self) -> Result<(), String> {
+    pub fn exec_seq(&mut self) -> Result<(), Trap> {
         self.parseand_exec_ixs_seq()?;
         Ok(())
     }
 
-    pub fn exec_concurrent(&mut self) -> Result<(), String> {
+    pub fn exec_concurrent(&mut self) -> Result<(), Trap> {
         self.parse_and_exec_ixs_concurrent()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `mov a, 5` / `mov b, 7` / `stm 0x00 0x00, b` / `add 0x00 0x00, a`: the
+    // two `mov`s (disjoint registers) batch together, while `stm`/`add`
+    // (both touch data address 0 and, for `add`, register `a`) serialize
+    // after them and after each other.
+    fn straight_line_program() -> Vec<u8> {
+        vec![
+            IxType::MOV as u8, 2, Register::A.into_usize() as u8, 5,
+            IxType::MOV as u8, 2, Register::B.into_usize() as u8, 7,
+            IxType::STM as u8, 3, 0x00, 0x00, Register::B.into_usize() as u8,
+            IxType::ADD as u8, 3, 0x00, 0x00, Register::A.into_usize() as u8,
+            IxType::NOP as u8, 0,
+        ]
+    }
+
+    fn load(vm: &mut VM, prog: &[u8]) {
+        vm.memory.write().unwrap().load_ix(0, prog).unwrap();
+    }
+
+    #[test]
+    fn concurrent_matches_sequential_on_straight_line_program() {
+        let mut seq = VM::new();
+        load(&mut seq, &straight_line_program());
+        seq.exec_seq().unwrap();
+
+        let mut conc = VM::new();
+        load(&mut conc, &straight_line_program());
+        conc.exec_concurrent().unwrap();
+
+        assert_eq!(*seq.registers.read().unwrap(), *conc.registers.read().unwrap());
+        assert_eq!(
+            seq.memory.read().unwrap().data_seg,
+            conc.memory.read().unwrap().data_seg
+        );
+    }
+
+    #[test]
+    fn concurrent_falls_back_to_sequential_when_timer_armed() {
+        let mut seq = VM::new();
+        load(&mut seq, &straight_line_program());
+        seq.exec_seq().unwrap();
+
+        let mut conc = VM::new();
+        load(&mut conc, &straight_line_program());
+        conc.set_timer(1000);
+        conc.exec_concurrent().unwrap();
+
+        assert_eq!(*seq.registers.read().unwrap(), *conc.registers.read().unwrap());
+    }
+
+    #[test]
+    fn invalid_opcode_traps() {
+        let mut vm = VM::new();
+        load(&mut vm, &[0xff, 0]);
+        assert_eq!(vm.exec_seq().unwrap_err(), Trap::InvalidInstruction(0xff));
+    }
+
+    #[test]
+    fn trap_handler_can_resume_after_patching_the_fault() {
+        // `mov` into invalid register 0xff traps once; the handler patches the
+        // opcode at `PC` into a `NOP` and resumes, so the run completes
+        // cleanly instead of aborting.
+        let mut vm = VM::new();
+        load(
+            &mut vm,
+            &[IxType::MOV as u8, 2, 0xff, 5, IxType::NOP as u8, 0],
+        );
+
+        let resumed = Arc::new(RwLock::new(false));
+        let resumed_cpy = Arc::clone(&resumed);
+        vm.set_trap_handler(Box::new(move |vm, trap| {
+            assert_eq!(trap, Trap::InvalidRegister(0xff));
+            *resumed_cpy.write().unwrap() = true;
+            let pc = vm.registers.read().unwrap()[Register::PC.into_usize()];
+            vm.memory
+                .write()
+                .unwrap()
+                .write_code_seg(IxType::NOP as u8, pc)
+                .unwrap();
+            TrapAction::Resume
+        }));
+
+        vm.exec_seq().unwrap();
+        assert!(*resumed.read().unwrap());
+    }
+
+    #[test]
+    fn divide_by_zero_traps() {
+        // `div [data addr 0], a, b`; data_seg[0] defaults to 0.
+        let prog = vec![
+            IxType::DIV as u8,
+            4,
+            0x00,
+            0x00,
+            Register::A.into_usize() as u8,
+            Register::B.into_usize() as u8,
+            IxType::NOP as u8,
+        ];
+        let mut vm = VM::new();
+        load(&mut vm, &prog);
+        assert_eq!(vm.exec_seq().unwrap_err(), Trap::DivideByZero);
+    }
+
+    #[test]
+    fn conditional_branch_follows_the_zero_flag() {
+        // `sub 0x00 0x00, a` against a data segment of zeros leaves `a` at 0,
+        // setting the zero flag; `jz` should then skip the `mov b, 1` and land
+        // on `mov c, 9`.
+        let prog = vec![
+            IxType::MOV as u8, 2, Register::A.into_usize() as u8, 0,
+            IxType::SUB as u8, 3, 0x00, 0x00, Register::A.into_usize() as u8,
+            IxType::JZ as u8, 2, 0x00, 17,
+            IxType::MOV as u8, 2, Register::B.into_usize() as u8, 1,
+            IxType::MOV as u8, 2, Register::C.into_usize() as u8, 9,
+            IxType::NOP as u8,
+        ];
+        let mut vm = VM::new();
+        load(&mut vm, &prog);
+        vm.exec_seq().unwrap();
+
+        let regs = vm.registers.read().unwrap();
+        assert_eq!(regs[Register::B.into_usize()], 0);
+        assert_eq!(regs[Register::C.into_usize()], 9);
+    }
+
+    #[test]
+    fn push_pop_round_trips_through_the_stack() {
+        let prog = vec![
+            IxType::MOV as u8, 2, Register::A.into_usize() as u8, 42,
+            IxType::PUSH as u8, 1, Register::A.into_usize() as u8,
+            IxType::POP as u8, 1, Register::B.into_usize() as u8,
+            IxType::NOP as u8,
+        ];
+        let mut vm = VM::new();
+        load(&mut vm, &prog);
+        vm.exec_seq().unwrap();
+
+        assert_eq!(vm.registers.read().unwrap()[Register::B.into_usize()], 42);
+    }
+
+    #[test]
+    fn timer_wrap_dispatches_interrupt_and_reti_resumes() {
+        let mut vm = VM::new();
+        {
+            let mut mem = vm.memory.write().unwrap();
+            mem.load_ix(
+                0,
+                &[
+                    IxType::MOV as u8, 2, Register::A.into_usize() as u8, 1,
+                    IxType::MOV as u8, 2, Register::B.into_usize() as u8, 2,
+                    IxType::MOV as u8, 2, Register::C.into_usize() as u8, 3,
+                    IxType::NOP as u8, 0,
+                ],
+            )
+            .unwrap();
+            // Interrupt handler: record that it ran, then return.
+            mem.load_ix(
+                100,
+                &[
+                    IxType::MOV as u8, 2, Register::D.into_usize() as u8, 99,
+                    IxType::RETI as u8, 0,
+                ],
+            )
+            .unwrap();
+        }
+        vm.set_interrupt_vector(100);
+        // Wraps after the third instruction, before the terminating NOP runs.
+        vm.set_timer(3);
+
+        vm.exec_seq().unwrap();
+
+        let regs = vm.registers.read().unwrap();
+        assert_eq!(regs[Register::A.into_usize()], 1);
+        assert_eq!(regs[Register::B.into_usize()], 2);
+        assert_eq!(regs[Register::C.into_usize()], 3);
+        assert_eq!(regs[Register::D.into_usize()], 99);
+    }
+
+    #[test]
+    fn concurrent_decode_trap_resumes_via_handler() {
+        let mut vm = VM::new();
+        load(&mut vm, &[0xff, 0]);
+
+        let resumed = Arc::new(RwLock::new(false));
+        let resumed_cpy = Arc::clone(&resumed);
+        vm.set_trap_handler(Box::new(move |vm, trap| {
+            assert_eq!(trap, Trap::InvalidInstruction(0xff));
+            *resumed_cpy.write().unwrap() = true;
+            let pc = vm.registers.read().unwrap()[Register::PC.into_usize()];
+            vm.memory
+                .write()
+                .unwrap()
+                .write_code_seg(IxType::NOP as u8, pc)
+                .unwrap();
+            TrapAction::Resume
+        }));
+
+        vm.exec_concurrent().unwrap();
+        assert!(*resumed.read().unwrap());
+    }
+
+    #[test]
+    fn concurrent_batch_trap_resumes_via_handler() {
+        // `mov` into invalid register 0xff traps inside the batch thread; the
+        // handler should see it via `dispatch_trap` rather than the run
+        // aborting on a bare `?`.
+        let mut vm = VM::new();
+        load(
+            &mut vm,
+            &[IxType::MOV as u8, 2, 0xff, 5, IxType::NOP as u8, 0],
+        );
+
+        let resumed = Arc::new(RwLock::new(false));
+        let resumed_cpy = Arc::clone(&resumed);
+        vm.set_trap_handler(Box::new(move |_vm, trap| {
+            assert_eq!(trap, Trap::InvalidRegister(0xff));
+            *resumed_cpy.write().unwrap() = true;
+            TrapAction::Resume
+        }));
+
+        vm.exec_concurrent().unwrap();
+        assert!(*resumed.read().unwrap());
+    }
+}